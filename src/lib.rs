@@ -4,11 +4,14 @@ extern crate redismodule;
 use redismodule::native_types::RedisType;
 use redismodule::{Context, NextArg, RedisError, RedisResult, RedisValue, REDIS_OK};
 use serde_json::{Number, Value};
-use std::{cmp, i64, usize};
+use std::{i64, usize};
 
+mod path_cache;
 mod redisjson;
+mod select_value;
 
-use crate::redisjson::{Error, RedisJSON};
+use crate::redisjson::{Error, Format, FormatOptions, RedisJSON, SetOptions};
+use crate::select_value::SelectValue;
 
 static JSON_TYPE_ENCODING_VERSION: i32 = 2;
 static JSON_TYPE_NAME: &str = "ReJSON-RL";
@@ -24,18 +27,11 @@ static REDIS_JSON_TYPE: RedisType = RedisType::new(
         aof_rewrite: None, // TODO add support
         free: Some(redisjson::json_free),
 
-        // Currently unused by Redis
-        mem_usage: None,
+        mem_usage: Some(redisjson::json_mem_usage),
         digest: None,
     },
 );
 
-#[derive(Debug, PartialEq)]
-pub enum SetOptions {
-    NotExists,
-    AlreadyExists,
-}
-
 ///
 /// Backwards compatibility convertor for RedisJSON 1.x clients
 ///
@@ -77,7 +73,36 @@ fn json_del(ctx: &Context, args: Vec<String>) -> RedisResult {
 }
 
 ///
-/// JSON.SET <key> <path> <json> [NX | XX]
+/// JSON.CLEAR <key> [path]
+///
+/// Empties arrays/objects and zeroes numbers reachable by `path` (defaulting
+/// to the root) in place, leaving the containers themselves intact. Returns
+/// the number of values cleared.
+///
+fn json_clear(ctx: &Context, args: Vec<String>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+
+    let key = args.next_string()?;
+    let path = backwards_compat_path(args.next_string().unwrap_or_else(|_| "$".to_owned()));
+
+    let key = ctx.open_key_writable(&key);
+    let cleared = match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
+        Some(doc) => doc.clear(&path)?,
+        None => 0,
+    };
+    Ok(cleared.into())
+}
+
+///
+/// JSON.SET <key> <path> <json> [NX | XX] [FORMAT JSON|MSGPACK]
+///
+/// Command arguments reach us as UTF-8 `String`s (see `NextArg::next_string`),
+/// so `FORMAT MSGPACK` on the way in only round-trips MessagePack payloads
+/// that are themselves valid UTF-8 — a non-UTF-8 payload is rejected when
+/// the arguments are converted to `String`s, before `json_set` ever runs.
+/// `JSON.GET ... FORMAT MSGPACK`
+/// has no such restriction: `to_msgpack` writes the reply bytes directly
+/// rather than going through a `String` argument.
 ///
 fn json_set(ctx: &Context, args: Vec<String>) -> RedisResult {
     let mut args = args.into_iter().skip(1);
@@ -86,27 +111,29 @@ fn json_set(ctx: &Context, args: Vec<String>) -> RedisResult {
     let path = backwards_compat_path(args.next_string()?);
     let value = args.next_string()?;
 
-    let set_option = args
-        .next()
-        .map(|op| match op.to_uppercase().as_str() {
-            "NX" => Ok(SetOptions::NotExists),
-            "XX" => Ok(SetOptions::AlreadyExists),
-            _ => Err(RedisError::Str("ERR syntax error")),
-        })
-        .transpose()?;
+    let mut set_option = SetOptions::None;
+    let mut format = Format::JSON;
+    while let Ok(arg) = args.next_string() {
+        match arg.to_uppercase().as_str() {
+            "NX" => set_option = SetOptions::NotExists,
+            "XX" => set_option = SetOptions::AlreadyExists,
+            "FORMAT" => format = Format::from_str(&args.next_string()?.to_uppercase())?,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
 
     let key = ctx.open_key_writable(&key);
     let current = key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)?;
 
-    match (current, set_option) {
-        (Some(_), Some(SetOptions::NotExists)) => Ok(().into()),
+    match (current, &set_option) {
+        (Some(_), SetOptions::NotExists) => Ok(().into()),
         (Some(ref mut doc), _) => {
-            doc.set_value(&value, &path)?;
+            doc.set_value(value.as_bytes(), &path, &set_option, format)?;
             REDIS_OK
         }
-        (None, Some(SetOptions::AlreadyExists)) => Ok(().into()),
+        (None, SetOptions::AlreadyExists) => Ok(().into()),
         (None, _) => {
-            let doc = RedisJSON::from_str(&value)?;
+            let doc = RedisJSON::from_bytes(value.as_bytes(), format)?;
             if path == "$" {
                 key.set_value(&REDIS_JSON_TYPE, doc)?;
                 REDIS_OK
@@ -122,15 +149,24 @@ fn json_set(ctx: &Context, args: Vec<String>) -> RedisResult {
 ///         [INDENT indentation-string]
 ///         [NEWLINE line-break-string]
 ///         [SPACE space-string]
+///         [FORMAT JSON|MSGPACK]
 ///         [NOESCAPE]
 ///         [path ...]
 ///
+/// FORMAT MSGPACK replies with the selected value packed via `rmp-serde` as a
+/// binary bulk string; INDENT/NEWLINE/SPACE only affect FORMAT JSON output.
+///
 /// TODO add support for multi path
 fn json_get(ctx: &Context, args: Vec<String>) -> RedisResult {
     let mut args = args.into_iter().skip(1);
 
     let key = args.next_string()?;
 
+    let mut indent = String::new();
+    let mut newline = String::new();
+    let mut space = String::new();
+    let mut format = Format::JSON;
+
     let mut path = loop {
         let arg = match args.next_string() {
             Ok(s) => s,
@@ -138,19 +174,25 @@ fn json_get(ctx: &Context, args: Vec<String>) -> RedisResult {
         };
 
         match arg.as_str() {
-            "INDENT" => args.next(),  // TODO add support
-            "NEWLINE" => args.next(), // TODO add support
-            "SPACE" => args.next(),   // TODO add support
+            "INDENT" => indent = args.next_string()?,
+            "NEWLINE" => newline = args.next_string()?,
+            "SPACE" => space = args.next_string()?,
+            "FORMAT" => format = Format::from_str(&args.next_string()?.to_uppercase())?,
             "NOESCAPE" => continue,   // TODO add support
             _ => break arg,
         };
     };
     path = backwards_compat_path(path);
 
+    let format_options = FormatOptions::new(&indent, &newline, &space);
+
     let key = ctx.open_key_writable(&key);
 
     let value = match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
-        Some(doc) => doc.to_string(&path)?.into(),
+        Some(doc) if format == Format::MSGPACK => doc.to_msgpack(&path)?.into(),
+        Some(doc) => doc
+            .to_string_with_format(&path, format, &format_options)?
+            .into(),
         None => ().into(),
     };
 
@@ -190,7 +232,7 @@ fn json_mget(ctx: &Context, args: Vec<String>) -> RedisResult {
 /// JSON.STRLEN <key> [path]
 ///
 fn json_str_len(ctx: &Context, args: Vec<String>) -> RedisResult {
-    json_len(ctx, args, |doc, path| doc.str_len(path))
+    json_len(ctx, args, RedisJSON::value_str_len)
 }
 
 ///
@@ -204,7 +246,21 @@ fn json_type(ctx: &Context, args: Vec<String>) -> RedisResult {
     let key = ctx.open_key(&key);
 
     let value = match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
-        Some(doc) => doc.get_type(&path)?.into(),
+        Some(doc) => {
+            let matches = doc.select(&path)?;
+            if RedisJSON::is_legacy_path(&path) {
+                match matches.first() {
+                    Some(v) => RedisJSON::value_name(*v).to_string().into(),
+                    None => return Err("ERR path does not exist".into()),
+                }
+            } else {
+                let types: Vec<RedisValue> = matches
+                    .into_iter()
+                    .map(|v| RedisJSON::value_name(v).to_string().into())
+                    .collect();
+                types.into()
+            }
+        }
         None => ().into(),
     };
 
@@ -248,8 +304,7 @@ where
         .ok_or_else(RedisError::nonexistent_key)
         .and_then(|doc| {
             doc.value_op(&path, |value| {
-                value
-                    .as_f64()
+                SelectValue::as_f64(value)
                     .ok_or_else(|| err_json(value, "number"))
                     .and_then(|curr_value| {
                         let res = fun(curr_value, number);
@@ -294,8 +349,7 @@ fn json_str_append(ctx: &Context, args: Vec<String>) -> RedisResult {
         .ok_or_else(RedisError::nonexistent_key)
         .and_then(|doc| {
             doc.value_op(&path, |value| {
-                value
-                    .as_str()
+                SelectValue::as_str(value)
                     .ok_or_else(|| err_json(value, "string"))
                     .and_then(|curr| {
                         let new_value = [curr, &json].concat();
@@ -321,26 +375,13 @@ fn json_arr_append(ctx: &Context, args: Vec<String>) -> RedisResult {
 
     let key = ctx.open_key_writable(&key);
 
+    let items: Vec<Value> = args
+        .map(|json| serde_json::from_str(&json))
+        .collect::<Result<_, _>>()?;
+
     key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)?
         .ok_or_else(RedisError::nonexistent_key)
-        .and_then(|doc| {
-            doc.value_op(&path, |value| {
-                value
-                    .as_array()
-                    .ok_or_else(|| err_json(value, "array"))
-                    .and_then(|curr| {
-                        let items: Vec<Value> = args
-                            .clone()
-                            .map(|json| serde_json::from_str(&json))
-                            .collect::<Result<_, _>>()?;
-
-                        let new_value = [curr.as_slice(), &items].concat();
-                        Ok(Value::Array(new_value))
-                    })
-            })
-            .map(|v| v.len().into())
-            .map_err(|e| e.into())
-        })
+        .and_then(|doc| Ok(doc.arr_append(&path, items)?.into()))
 }
 
 ///
@@ -365,15 +406,15 @@ fn json_arr_index(ctx: &Context, args: Vec<String>) -> RedisResult {
         0
     };
 
-    let end = if args_len >= 6 {
+    let stop = if args_len >= 6 {
         args.next_string()?.parse()?
     } else {
-        usize::MAX
+        0
     };
 
     let key = ctx.open_key(&key);
     let index: i64 = match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
-        Some(doc) => doc.arr_index(&path, &json_scalar, start, end)?,
+        Some(doc) => doc.arr_index(&path, &json_scalar, start, stop)?,
         None => -1,
     };
 
@@ -388,47 +429,15 @@ fn json_arr_insert(ctx: &Context, args: Vec<String>) -> RedisResult {
 
     let key = args.next_string()?;
     let path = backwards_compat_path(args.next_string()?);
-    let mut index: i64 = args.next_string()?.parse()?;
-    let mut json = args.next_string()?;
+    let index: i64 = args.next_string()?.parse()?;
+    let values: Vec<Value> = args
+        .map(|json| serde_json::from_str(&json))
+        .collect::<Result<_, _>>()?;
 
     let key = ctx.open_key_writable(&key);
 
     match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
-        Some(doc) => Ok(doc
-            .value_op(&path, |value| {
-                if let Value::Array(curr) = value {
-                    let len = curr.len() as i64;
-                    if i64::abs(index) >= len {
-                        Err("ERR index out of bounds".into())
-                    } else {
-                        if index < 0 {
-                            index = len + index;
-                        }
-
-                        let mut res = curr.clone();
-
-                        loop {
-                            let value = serde_json::from_str(json.as_str())?;
-                            res.insert(index as usize, value);
-                            index = index + 1;
-                            // path is optional
-                            if let Ok(val) = args.next_string() {
-                                json = val;
-                            } else {
-                                break;
-                            }
-                        }
-                        Ok(Value::Array(res))
-                    }
-                } else {
-                    Err(format!(
-                        "ERR wrong type of path value - expected a string but found {}",
-                        RedisJSON::value_name(&value)
-                    )
-                    .into())
-                }
-            })?
-            .into()),
+        Some(doc) => Ok(doc.arr_insert(&path, index, values)?.into()),
         None => Err("ERR could not perform this operation on a key that doesn't exist".into()),
     }
 }
@@ -437,7 +446,7 @@ fn json_arr_insert(ctx: &Context, args: Vec<String>) -> RedisResult {
 /// JSON.ARRLEN <key> [path]
 ///
 fn json_arr_len(ctx: &Context, args: Vec<String>) -> RedisResult {
-    json_len(ctx, args, |doc, path| doc.arr_len(path))
+    json_len(ctx, args, RedisJSON::value_arr_len)
 }
 
 ///
@@ -446,7 +455,7 @@ fn json_arr_len(ctx: &Context, args: Vec<String>) -> RedisResult {
 fn json_arr_pop(ctx: &Context, args: Vec<String>) -> RedisResult {
     let mut args = args.into_iter().skip(1);
     let key = args.next_string()?;
-    let (path, mut index): (String, i64) = if let Ok(mut p) = args.next_string() {
+    let (path, index): (String, i64) = if let Ok(mut p) = args.next_string() {
         p = backwards_compat_path(p);
         if let Ok(i) = args.next_string() {
             (p, i.parse()?)
@@ -460,31 +469,7 @@ fn json_arr_pop(ctx: &Context, args: Vec<String>) -> RedisResult {
     let key = ctx.open_key_writable(&key);
 
     match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
-        Some(doc) => {
-            let mut res = Value::Null;
-            doc.value_op(&path, |value| {
-                if let Value::Array(curr) = value {
-                    index = cmp::min(index, curr.len() as i64 - 1);
-                    if index < 0 {
-                        index = curr.len() as i64 + index;
-                    }
-                    if index >= curr.len() as i64 || index < 0 {
-                        Err("ERR index out of bounds".into())
-                    } else {
-                        let mut curr_clone = curr.clone();
-                        res = curr_clone.remove(index as usize);
-                        Ok(Value::Array(curr_clone))
-                    }
-                } else {
-                    Err(format!(
-                        "ERR wrong type of path value - expected a array but found {}",
-                        RedisJSON::value_name(&value)
-                    )
-                    .into())
-                }
-            })?;
-            Ok(res.to_string().into())
-        }
+        Some(doc) => Ok(doc.arr_pop(&path, index)?.to_string().into()),
         None => Err("ERR could not perform this operation on a key that doesn't exist".into()),
     }
 }
@@ -497,29 +482,13 @@ fn json_arr_trim(ctx: &Context, args: Vec<String>) -> RedisResult {
 
     let key = args.next_string()?;
     let path = backwards_compat_path(args.next_string()?);
-    let mut start: usize = args.next_string()?.parse()?;
-    let mut stop: usize = args.next_string()?.parse()?;
+    let start: i64 = args.next_string()?.parse()?;
+    let stop: i64 = args.next_string()?.parse()?;
 
     let key = ctx.open_key_writable(&key);
 
     match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
-        Some(doc) => Ok(doc
-            .value_op(&path, |value| {
-                if let Value::Array(curr) = value {
-                    start = cmp::max(start, 0);
-                    stop = cmp::min(stop, curr.len() - 1);
-                    start = cmp::min(stop, start);
-                    let res = &curr[start..stop];
-                    Ok(Value::Array(res.to_vec()))
-                } else {
-                    Err(format!(
-                        "ERR wrong type of path value - expected a array but found {}",
-                        RedisJSON::value_name(&value)
-                    )
-                    .into())
-                }
-            })?
-            .into()),
+        Some(doc) => Ok(doc.arr_trim(&path, start, stop)?.into()),
         None => Err("ERR could not perform this operation on a key that doesn't exist".into()),
     }
 }
@@ -535,7 +504,21 @@ fn json_obj_keys(ctx: &Context, args: Vec<String>) -> RedisResult {
     let key = ctx.open_key(&key);
 
     let value = match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
-        Some(doc) => doc.obj_keys(&path)?.into(),
+        Some(doc) => {
+            let matches = doc.select(&path)?;
+            if RedisJSON::is_legacy_path(&path) {
+                match matches.first() {
+                    Some(v) => RedisJSON::value_obj_keys(*v)?.into(),
+                    None => return Err("ERR path does not exist".into()),
+                }
+            } else {
+                let keys: Result<Vec<RedisValue>, Error> = matches
+                    .into_iter()
+                    .map(|v| RedisJSON::value_obj_keys(v).map(Into::into))
+                    .collect();
+                keys?.into()
+            }
+        }
         None => ().into(),
     };
 
@@ -546,7 +529,7 @@ fn json_obj_keys(ctx: &Context, args: Vec<String>) -> RedisResult {
 /// JSON.OBJLEN <key> [path]
 ///
 fn json_obj_len(ctx: &Context, args: Vec<String>) -> RedisResult {
-    json_len(ctx, args, |doc, path| doc.obj_len(path))
+    json_len(ctx, args, RedisJSON::value_obj_len)
 }
 
 ///
@@ -556,18 +539,59 @@ fn json_obj_len(ctx: &Context, args: Vec<String>) -> RedisResult {
 /// MEMORY <key> [path]
 /// HELP
 ///
-fn json_debug(_ctx: &Context, _args: Vec<String>) -> RedisResult {
-    Err("Command was not implemented".into())
+fn json_debug(ctx: &Context, args: Vec<String>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let subcommand = args.next_string()?;
+
+    match subcommand.to_uppercase().as_str() {
+        "MEMORY" => {
+            let key = args.next_string()?;
+            let path = backwards_compat_path(
+                args.next_string().unwrap_or_else(|_| "$".to_owned()),
+            );
+
+            let key = ctx.open_key(&key);
+            let mem = match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
+                Some(doc) => doc.memory_usage(&path)?.into(),
+                None => ().into(),
+            };
+            Ok(mem)
+        }
+        "HELP" => {
+            let results: Vec<String> = vec![
+                "MEMORY <key> [path] - reports memory usage".to_string(),
+                "HELP                - print this help".to_string(),
+            ];
+            Ok(results.into())
+        }
+        _ => Err(RedisError::String(
+            "ERR unknown subcommand - try `JSON.DEBUG HELP`".to_string(),
+        )),
+    }
 }
 
 ///
 /// JSON.RESP <key> [path]
 ///
-fn json_resp(_ctx: &Context, _args: Vec<String>) -> RedisResult {
-    Err("Command was not implemented".into())
+fn json_resp(ctx: &Context, args: Vec<String>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_string()?;
+    let path = backwards_compat_path(args.next_string().unwrap_or_else(|_| "$".to_owned()));
+
+    let key = ctx.open_key(&key);
+
+    let value = match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
+        Some(doc) => doc.resp_serialize(&path)?,
+        None => ().into(),
+    };
+
+    Ok(value)
 }
 
-fn json_len<F: Fn(&RedisJSON, &String) -> Result<usize, Error>>(
+/// Shared body for STRLEN/ARRLEN/OBJLEN: a legacy path replies with a single
+/// scalar (or an error if the path matches nothing), an enhanced path replies
+/// with an array of one result per match. A missing key replies with nil.
+fn json_len<F: Fn(&Value) -> Result<usize, Error>>(
     ctx: &Context,
     args: Vec<String>,
     fun: F,
@@ -578,19 +602,70 @@ fn json_len<F: Fn(&RedisJSON, &String) -> Result<usize, Error>>(
 
     let key = ctx.open_key(&key);
     let length = match key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)? {
-        Some(doc) => fun(&doc, &path)?.into(),
+        Some(doc) => {
+            let matches = doc.select(&path)?;
+            if RedisJSON::is_legacy_path(&path) {
+                match matches.first() {
+                    Some(v) => fun(v)?.into(),
+                    None => return Err("ERR path does not exist".into()),
+                }
+            } else {
+                let lens: Result<Vec<RedisValue>, Error> =
+                    matches.into_iter().map(|v| fun(v).map(Into::into)).collect();
+                lens?.into()
+            }
+        }
         None => ().into(),
     };
 
     Ok(length)
 }
 
+///
+/// JSON._CACHEINFO
+///
+/// Reports the compiled-path cache's occupancy and hit/miss counters as a
+/// flat `size capacity hits misses` reply. Errors if `JSON._CACHEINIT` has
+/// not been run yet.
+///
 fn json_cache_info(_ctx: &Context, _args: Vec<String>) -> RedisResult {
-    Err("Command was not implemented".into())
+    match path_cache::info() {
+        Some(info) => {
+            let reply: Vec<RedisValue> = vec![
+                "size".to_string().into(),
+                info.size.into(),
+                "capacity".to_string().into(),
+                info.capacity.into(),
+                "hits".to_string().into(),
+                info.hits.into(),
+                "misses".to_string().into(),
+                info.misses.into(),
+            ];
+            Ok(reply.into())
+        }
+        None => Err("ERR cache not initialized - run JSON._CACHEINIT first".into()),
+    }
 }
 
-fn json_cache_init(_ctx: &Context, _args: Vec<String>) -> RedisResult {
-    Err("Command was not implemented".into())
+///
+/// JSON._CACHEINIT <max_entries>
+///
+/// (Re)initializes the compiled-path cache with room for `max_entries`
+/// read selectors and as many write selectors, discarding anything already
+/// cached.
+///
+fn json_cache_init(_ctx: &Context, args: Vec<String>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let max_entries = args
+        .next_string()?
+        .parse::<usize>()
+        .map_err(|_| RedisError::Str("ERR max_entries must be a positive integer"))?;
+    if max_entries == 0 {
+        return Err(RedisError::Str("ERR max_entries must be a positive integer"));
+    }
+
+    path_cache::init(max_entries);
+    REDIS_OK
 }
 //////////////////////////////////////////////////////
 
@@ -602,6 +677,7 @@ redis_module! {
     ],
     commands: [
         ["json.del", json_del, "write"],
+        ["json.clear", json_clear, "write"],
         ["json.get", json_get, ""],
         ["json.mget", json_mget, ""],
         ["json.set", json_set, "write"],
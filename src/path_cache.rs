@@ -0,0 +1,125 @@
+//! Module-global cache of compiled JSONPath selectors.
+//!
+//! Parsing a path expression into its selector/AST is the expensive part of
+//! every path-taking command; this cache memoizes that step, keyed by the raw
+//! path string, so workloads that repeatedly query the same handful of paths
+//! skip re-parsing. `JSON._CACHEINIT` installs the cache with a given
+//! capacity and resets its counters; `JSON._CACHEINFO` reports its occupancy
+//! and hit/miss counters. Until `JSON._CACHEINIT` has been called, `select`/
+//! `replace_with` simply fall back to compiling on every call, so every other
+//! command keeps working unchanged.
+//!
+//! Reads (`select`) and writes (`replace_with`) go through separate caches
+//! because `jsonpath_lib` compiles them into different reusable types (a
+//! read-only selector closure vs. a `SelectorMut`), but both are consulted on
+//! every path-taking command and both contribute to the hit/miss counters.
+
+use jsonpath_lib::{JsonPathError, SelectorMut};
+use lru::LruCache;
+use serde_json::Value;
+use std::sync::Mutex;
+
+type CompiledPath = Box<dyn for<'a> FnMut(&'a Value) -> Result<Vec<&'a Value>, JsonPathError> + Send>;
+
+struct PathCache {
+    entries: LruCache<String, CompiledPath>,
+    mut_entries: LruCache<String, SelectorMut>,
+    hits: usize,
+    misses: usize,
+}
+
+lazy_static::lazy_static! {
+    static ref CACHE: Mutex<Option<PathCache>> = Mutex::new(None);
+}
+
+/// Occupancy and hit/miss counters reported by `JSON._CACHEINFO`.
+pub struct CacheInfo {
+    pub size: usize,
+    pub capacity: usize,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// (Re)initializes the global path cache with `capacity` entries in each of
+/// the read and write caches, discarding any previously cached selectors and
+/// counters.
+pub fn init(capacity: usize) {
+    *CACHE.lock().unwrap() = Some(PathCache {
+        entries: LruCache::new(capacity),
+        mut_entries: LruCache::new(capacity),
+        hits: 0,
+        misses: 0,
+    });
+}
+
+/// `None` if `JSON._CACHEINIT` hasn't been run yet.
+pub fn info() -> Option<CacheInfo> {
+    CACHE.lock().unwrap().as_ref().map(|cache| CacheInfo {
+        size: cache.entries.len() + cache.mut_entries.len(),
+        capacity: cache.entries.cap(),
+        hits: cache.hits,
+        misses: cache.misses,
+    })
+}
+
+/// Selects every value matched by `path` in `value`, compiling and caching
+/// the selector on a miss. Falls back to an uncached compile on every call
+/// when the cache has not been initialized.
+pub fn select<'a>(value: &'a Value, path: &str) -> Result<Vec<&'a Value>, JsonPathError> {
+    let mut guard = CACHE.lock().unwrap();
+    match guard.as_mut() {
+        Some(cache) => {
+            if let Some(compiled) = cache.entries.get_mut(path) {
+                cache.hits += 1;
+                return compiled(value);
+            }
+            cache.misses += 1;
+            let mut compiled = jsonpath_lib::compile(path);
+            let result = compiled(value);
+            cache.entries.put(path.to_owned(), Box::new(compiled));
+            result
+        }
+        None => jsonpath_lib::compile(path)(value),
+    }
+}
+
+/// Write-side counterpart of `select`: replaces every value matched by
+/// `path` in `value` with whatever `fun` returns (`None` deletes the match),
+/// compiling and caching a `SelectorMut` on a miss. Used by every mutating
+/// command (`JSON.SET`/`DEL`/`CLEAR`/`NUMINCRBY`/`ARRAPPEND`/...) in place of
+/// calling `jsonpath_lib::replace_with` directly, so writes are cached too
+/// (in their own LRU) and counted in the same hit/miss counters as reads.
+pub fn replace_with<F>(value: Value, path: &str, fun: &mut F) -> Result<Value, JsonPathError>
+where
+    F: FnMut(Value) -> Option<Value>,
+{
+    let mut guard = CACHE.lock().unwrap();
+    match guard.as_mut() {
+        Some(cache) => {
+            if let Some(selector) = cache.mut_entries.get_mut(path) {
+                cache.hits += 1;
+                return Ok(selector
+                    .value(value)
+                    .replace_with(fun)?
+                    .take()
+                    .unwrap_or(Value::Null));
+            }
+            cache.misses += 1;
+            let mut selector = SelectorMut::new();
+            selector.str_path(path)?;
+            let result = selector
+                .value(value)
+                .replace_with(fun)?
+                .take()
+                .unwrap_or(Value::Null);
+            cache.mut_entries.put(path.to_owned(), selector);
+            Ok(result)
+        }
+        None => Ok(SelectorMut::new()
+            .str_path(path)?
+            .value(value)
+            .replace_with(fun)?
+            .take()
+            .unwrap_or(Value::Null)),
+    }
+}
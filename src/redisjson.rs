@@ -7,10 +7,13 @@
 use bson::decode_document;
 use crate::backward;
 use crate::nodevisitor::NodeVisitorImpl;
-use jsonpath_lib::{JsonPathError, SelectorMut};
-use redismodule::raw;
+use crate::select_value::{SelectValue, SelectValueType};
+use jsonpath_lib::JsonPathError;
+use redismodule::{raw, RedisValue};
+use serde::Serialize;
+use serde_json::ser::Formatter;
 use serde_json::{Map, Value};
-use std::io::Cursor;
+use std::io::{Cursor, Write};
 use std::mem;
 use std::os::raw::{c_int, c_void};
 
@@ -26,6 +29,36 @@ pub enum SetOptions {
     None,
 }
 
+/// Normalizes a Redis-style `[start, stop]` array range against `len`: negative
+/// indices count from the end, `stop == 0` means "to the end of the array" (the
+/// ARRINDEX convention when no explicit stop is given), and both bounds are
+/// clamped to `[0, len)`. Returns `None` for an empty array or when the
+/// normalized `start` ends up after `stop`, so callers can report "not found"
+/// instead of underflowing.
+pub fn normalize_arr_indices(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    if len == 0 {
+        return None;
+    }
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start.min(len - 1)
+    };
+    let stop = if stop == 0 {
+        len - 1
+    } else if stop < 0 {
+        (len + stop).max(0)
+    } else {
+        stop.min(len - 1)
+    };
+    if stop < 0 || start > stop {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
 impl From<String> for Error {
     fn from(e: String) -> Self {
         Error { msg: e }
@@ -52,6 +85,24 @@ impl From<JsonPathError> for Error {
     }
 }
 
+impl From<serde_yaml::Error> for Error {
+    fn from(e: serde_yaml::Error) -> Self {
+        Error { msg: e.to_string() }
+    }
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        Error { msg: e.to_string() }
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        Error { msg: e.to_string() }
+    }
+}
+
 impl From<Error> for redismodule::RedisError {
     fn from(e: Error) -> Self {
         redismodule::RedisError::String(e.msg)
@@ -62,6 +113,8 @@ impl From<Error> for redismodule::RedisError {
 pub enum Format {
     JSON,
     BSON,
+    YAML,
+    MSGPACK,
 }
 
 impl Format {
@@ -69,11 +122,139 @@ impl Format {
         match s {
             "JSON" => Ok(Format::JSON),
             "BSON" => Ok(Format::BSON),
+            "YAML" => Ok(Format::YAML),
+            "MSGPACK" => Ok(Format::MSGPACK),
             _ => return Err("ERR wrong format".into()),
         }
     }
 }
 
+/// Output formatting knobs for `JSON.GET`-style replies (INDENT / NEWLINE / SPACE).
+///
+/// Each field is the literal byte string to emit in that position; leaving all
+/// three empty reproduces today's compact `serde_json::to_string` output exactly.
+#[derive(Debug, Default, Clone)]
+pub struct FormatOptions {
+    indent: String,
+    newline: String,
+    space: String,
+}
+
+impl FormatOptions {
+    pub fn new(indent: &str, newline: &str, space: &str) -> Self {
+        FormatOptions {
+            indent: indent.to_string(),
+            newline: newline.to_string(),
+            space: space.to_string(),
+        }
+    }
+
+    fn is_compact(&self) -> bool {
+        self.indent.is_empty() && self.newline.is_empty() && self.space.is_empty()
+    }
+}
+
+/// A `serde_json::ser::Formatter` driven by [`FormatOptions`], so `JSON.GET` can
+/// reproduce the INDENT/NEWLINE/SPACE behaviour of RedisJSON 1.x clients.
+struct RedisJsonFormatter<'a> {
+    options: &'a FormatOptions,
+    depth: usize,
+    // One entry per currently-open array/object, set by begin_array_value /
+    // begin_object_key once
+    // that container has written its first element, so end_array/end_object
+    // can tell an empty container (`[]`/`{}`) apart from one with elements.
+    had_value: Vec<bool>,
+}
+
+impl<'a> RedisJsonFormatter<'a> {
+    fn new(options: &'a FormatOptions) -> Self {
+        RedisJsonFormatter {
+            options,
+            depth: 0,
+            had_value: Vec::new(),
+        }
+    }
+
+    fn write_newline_indent<W: ?Sized + Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(self.options.newline.as_bytes())?;
+        for _ in 0..self.depth {
+            writer.write_all(self.options.indent.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Formatter for RedisJsonFormatter<'a> {
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.depth += 1;
+        self.had_value.push(false);
+        writer.write_all(b"[")
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.depth -= 1;
+        if self.had_value.pop().unwrap_or(false) {
+            self.write_newline_indent(writer)?;
+        }
+        writer.write_all(b"]")
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if let Some(had_value) = self.had_value.last_mut() {
+            *had_value = true;
+        }
+        if !first {
+            writer.write_all(b",")?;
+        }
+        self.write_newline_indent(writer)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, _writer: &mut W) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.depth += 1;
+        self.had_value.push(false);
+        writer.write_all(b"{")
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.depth -= 1;
+        if self.had_value.pop().unwrap_or(false) {
+            self.write_newline_indent(writer)?;
+        }
+        writer.write_all(b"}")
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if let Some(had_value) = self.had_value.last_mut() {
+            *had_value = true;
+        }
+        if !first {
+            writer.write_all(b",")?;
+        }
+        self.write_newline_indent(writer)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(b":")?;
+        writer.write_all(self.options.space.as_bytes())
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, _writer: &mut W) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct RedisJSON {
     data: Value,
@@ -95,6 +276,11 @@ impl RedisJSON {
                     Ok(v)
                 })
                 .unwrap_or_else(|e| Err(e.to_string().into())),
+            Format::YAML => Ok(serde_yaml::from_str(data)?),
+            // MessagePack is a binary format; a `&str` has already been forced
+            // through UTF-8 validation by the time it reaches here, which either
+            // rejects or mangles a real payload. Use `parse_bytes`/`from_bytes`.
+            Format::MSGPACK => Err("ERR use from_bytes for MSGPACK input".into()),
         }
     }
 
@@ -102,6 +288,25 @@ impl RedisJSON {
         let value = RedisJSON::parse_str(data, format)?;
         Ok(Self { data: value })
     }
+
+    /// Byte-oriented counterpart of `parse_str`, for formats (MessagePack)
+    /// whose wire representation isn't valid UTF-8 and so can't round-trip
+    /// through a `&str` without being rejected or mangled. Note this only
+    /// helps callers that already have raw bytes in hand: `JSON.SET`'s
+    /// argument reaches us as a `String` (command arguments are UTF-8 by the
+    /// time our handler sees them), so `FORMAT MSGPACK` input is limited to
+    /// UTF-8-clean payloads regardless of this function taking `&[u8]`.
+    pub fn parse_bytes(data: &[u8], format: Format) -> Result<Value, Error> {
+        match format {
+            Format::MSGPACK => Ok(rmp_serde::from_slice(data)?),
+            _ => RedisJSON::parse_str(std::str::from_utf8(data).map_err(|e| e.to_string())?, format),
+        }
+    }
+
+    pub fn from_bytes(data: &[u8], format: Format) -> Result<Self, Error> {
+        let value = RedisJSON::parse_bytes(data, format)?;
+        Ok(Self { data: value })
+    }
     fn add_value(&mut self, path: &str, value: Value) -> Result<bool, Error> {
         if NodeVisitorImpl::check(path)? {
             let mut splits = path.rsplitn(2, '.');
@@ -124,7 +329,7 @@ impl RedisJSON {
                 Ok(res)
             } else {
                 let mut set = false;
-                self.data = jsonpath_lib::replace_with(current_data, prefix, &mut |mut ret| {
+                self.data = crate::path_cache::replace_with(current_data, prefix, &mut |mut ret| {
                     if let Value::Object(ref mut map) = ret {
                         if map.contains_key(key) {
                             set = false;
@@ -144,12 +349,12 @@ impl RedisJSON {
 
     pub fn set_value(
         &mut self,
-        data: &str,
+        data: &[u8],
         path: &str,
         option: &SetOptions,
         format: Format
     ) -> Result<bool, Error> {
-        let json: Value = RedisJSON::parse_str(data, format)?;
+        let json: Value = RedisJSON::parse_bytes(data, format)?;
         if path == "$" {
             if SetOptions::NotExists == *option {
                 Ok(false)
@@ -161,7 +366,7 @@ impl RedisJSON {
             let mut replaced = false;
             if SetOptions::NotExists != *option {
                 let current_data = self.data.take();
-                self.data = jsonpath_lib::replace_with(current_data, path, &mut |_v| {
+                self.data = crate::path_cache::replace_with(current_data, path, &mut |_v| {
                     replaced = true;
                     Some(json.clone())
                 })?;
@@ -180,7 +385,7 @@ impl RedisJSON {
         let current_data = self.data.take();
 
         let mut deleted = 0;
-        self.data = jsonpath_lib::replace_with(current_data, path, &mut |v| {
+        self.data = crate::path_cache::replace_with(current_data, path, &mut |v| {
             if !v.is_null() {
                 deleted = deleted + 1; // might delete more than a single value
             }
@@ -189,29 +394,95 @@ impl RedisJSON {
         Ok(deleted)
     }
 
+    /// Empties every container matched by `path` and zeroes every number, keeping the
+    /// document's shape intact (unlike `delete_path`, which removes the matched node).
+    /// Strings, booleans and null are left untouched. Returns the count of values changed.
+    pub fn clear(&mut self, path: &str) -> Result<usize, Error> {
+        let mut cleared = 0;
+        let mut clear_value = |v: Value| -> Value {
+            let cleared_value = match v {
+                Value::Array(arr) if !arr.is_empty() => Some(Value::Array(vec![])),
+                Value::Object(map) if !map.is_empty() => Some(Value::Object(Map::new())),
+                Value::Number(ref n) if n.as_f64() != Some(0.0) => {
+                    Some(Value::Number(0.into()))
+                }
+                _ => None,
+            };
+            if let Some(new_value) = cleared_value {
+                cleared += 1;
+                new_value
+            } else {
+                v
+            }
+        };
+
+        if path == "$" {
+            let current_data = self.data.take();
+            self.data = clear_value(current_data);
+        } else {
+            let current_data = self.data.take();
+            self.data = crate::path_cache::replace_with(current_data, path, &mut |v| {
+                Some(clear_value(v))
+            })?;
+        }
+        Ok(cleared)
+    }
+
     pub fn to_string(&self, path: &str, format: Format) -> Result<String, Error> {
+        self.to_string_with_format(path, format, &FormatOptions::default())
+    }
+
+    pub fn to_string_with_format(
+        &self,
+        path: &str,
+        format: Format,
+        options: &FormatOptions,
+    ) -> Result<String, Error> {
         let results = self.get_doc(path)?;
         let res = match format {
-            Format::JSON => serde_json::to_string(&results)?,
-            Format::BSON => return Err("Soon to come...".into()) //results.into() as Bson,
+            Format::JSON => Self::serialize_with_options(results, options)?,
+            Format::BSON => return Err("Soon to come...".into()), //results.into() as Bson,
+            Format::YAML => serde_yaml::to_string(results)?,
+            // MessagePack is a binary format; serving it through a String-returning
+            // API would mangle it, so it's only reachable via `to_msgpack`'s
+            // dedicated binary reply path (FORMAT MSGPACK on JSON.GET).
+            Format::MSGPACK => return Err("ERR use to_msgpack for MSGPACK output".into()),
         };
         Ok(res)
     }
 
-    pub fn to_json(&self, paths: &mut Vec<String>) -> Result<String, Error> {
+    /// Serializes the value at `path` to MessagePack bytes (FORMAT MSGPACK on JSON.GET).
+    pub fn to_msgpack(&self, path: &str) -> Result<Vec<u8>, Error> {
+        Ok(rmp_serde::to_vec(self.get_doc(path)?)?)
+    }
+
+    fn serialize_with_options(value: &Value, options: &FormatOptions) -> Result<String, Error> {
+        if options.is_compact() {
+            return Ok(serde_json::to_string(value)?);
+        }
+        let mut out = Vec::new();
+        let mut formatter = RedisJsonFormatter::new(options);
+        let mut ser = serde_json::Serializer::with_formatter(&mut out, &mut formatter);
+        value.serialize(&mut ser)?;
+        Ok(String::from_utf8(out).map_err(|e| e.to_string())?)
+    }
+
+    pub fn to_json(&self, paths: &mut Vec<String>, options: &FormatOptions) -> Result<String, Error> {
         let mut selector = jsonpath_lib::selector(&self.data);
         let mut result = paths.drain(..).fold(String::from("{"), |mut acc, path| {
             let value = match selector(&path) {
                 Ok(s) => match s.first() {
-                    Some(v) => v,
+                    Some(v) => *v,
                     None => &Value::Null,
                 },
                 Err(_) => &Value::Null,
             };
+            let formatted =
+                Self::serialize_with_options(value, options).unwrap_or_else(|_| "null".to_string());
             acc.push('\"');
             acc.push_str(&path);
             acc.push_str("\":");
-            acc.push_str(value.to_string().as_str());
+            acc.push_str(formatted.as_str());
             acc.push(',');
             acc
         });
@@ -223,74 +494,197 @@ impl RedisJSON {
     }
 
     pub fn str_len(&self, path: &str) -> Result<usize, Error> {
-        self.get_doc(path)?
+        Self::value_str_len(self.get_doc(path)?)
+    }
+
+    pub(crate) fn value_str_len<T: SelectValue>(value: &T) -> Result<usize, Error> {
+        value
             .as_str()
             .ok_or_else(|| "ERR wrong type of path value".into())
             .map(|s| s.len())
     }
 
     pub fn arr_len(&self, path: &str) -> Result<usize, Error> {
-        self.get_doc(path)?
+        Self::value_arr_len(self.get_doc(path)?)
+    }
+
+    pub(crate) fn value_arr_len<T: SelectValue>(value: &T) -> Result<usize, Error> {
+        value
             .as_array()
             .ok_or_else(|| "ERR wrong type of path value".into())
             .map(|arr| arr.len())
     }
 
     pub fn obj_len(&self, path: &str) -> Result<usize, Error> {
-        self.get_doc(path)?
-            .as_object()
+        Self::value_obj_len(self.get_doc(path)?)
+    }
+
+    pub(crate) fn value_obj_len<T: SelectValue>(value: &T) -> Result<usize, Error> {
+        value
+            .len()
+            .filter(|_| value.value_type() == SelectValueType::Object)
             .ok_or_else(|| "ERR wrong type of path value".into())
-            .map(|obj| obj.len())
     }
 
     pub fn obj_keys<'a>(&'a self, path: &'a str) -> Result<Vec<&'a String>, Error> {
-        self.get_doc(path)?
-            .as_object()
+        Self::value_obj_keys(self.get_doc(path)?)
+    }
+
+    pub(crate) fn value_obj_keys<'a, T: SelectValue>(value: &'a T) -> Result<Vec<&'a String>, Error> {
+        value
+            .keys()
             .ok_or_else(|| "ERR wrong type of path value".into())
-            .map(|obj| obj.keys().collect())
+            .map(|keys| keys.collect())
     }
 
     pub fn arr_index(
         &self,
         path: &str,
         scalar: &str,
-        start: usize,
-        end: usize,
+        start: i64,
+        stop: i64,
     ) -> Result<i64, Error> {
-        if let Value::Array(arr) = self.get_doc(path)? {
+        let value = self.get_doc(path)?;
+        if let Some(arr) = SelectValue::as_array(value) {
             match serde_json::from_str(scalar)? {
                 Value::Array(_) | Value::Object(_) => Ok(-1),
-                v => {
-                    let mut start = start.max(0);
-                    let end = end.min(arr.len() - 1);
-                    start = end.min(start);
-
-                    let slice = &arr[start..=end];
-                    match slice.iter().position(|r| r == &v) {
-                        Some(i) => Ok((start + i) as i64),
-                        None => Ok(-1),
+                v => match normalize_arr_indices(start, stop, arr.len()) {
+                    None => Ok(-1),
+                    Some((start, stop)) => {
+                        let slice = &arr[start..=stop];
+                        match slice.iter().position(|r| r == &v) {
+                            Some(i) => Ok((start + i) as i64),
+                            None => Ok(-1),
+                        }
                     }
-                }
+                },
             }
         } else {
             Ok(-1)
         }
     }
 
+    /// Runs `fun` against each owned array matched at `path`, in place,
+    /// without cloning the rest of the document tree, and returns the result
+    /// of the last match. Used by the array mutators below so large arrays
+    /// aren't rebuilt on every append/pop.
+    fn mutate_array<F, R>(&mut self, path: &str, mut fun: F) -> Result<R, Error>
+    where
+        F: FnMut(Vec<Value>) -> Result<(Vec<Value>, R), (Vec<Value>, Error)>,
+        R: Default,
+    {
+        let current_data = self.data.take();
+        let mut errors = vec![];
+        let mut result = R::default();
+
+        let mut apply = |v: Value| -> Option<Value> {
+            match v {
+                Value::Array(arr) => match fun(arr) {
+                    Ok((new_arr, r)) => {
+                        result = r;
+                        Some(Value::Array(new_arr))
+                    }
+                    // A rejected mutation must not touch the document: hand the
+                    // original array straight back instead of replacing it.
+                    Err((original, e)) => {
+                        errors.push(e);
+                        Some(Value::Array(original))
+                    }
+                },
+                other => {
+                    errors.push(
+                        format!(
+                            "ERR wrong type of path value - expected array but found {}",
+                            RedisJSON::value_name(&other)
+                        )
+                        .into(),
+                    );
+                    Some(other)
+                }
+            }
+        };
+
+        self.data = if path == "$" {
+            // root needs special handling, mirroring `value_op`
+            apply(current_data).unwrap_or(Value::Null)
+        } else {
+            crate::path_cache::replace_with(current_data, path, &mut apply)?
+        };
+
+        match errors.len() {
+            0 => Ok(result),
+            1 => Err(errors.remove(0)),
+            _ => Err(errors.into_iter().map(|e| e.msg).collect::<String>().into()),
+        }
+    }
+
+    /// Appends `values` to the array at `path` in place and returns its new length.
+    pub fn arr_append(&mut self, path: &str, values: Vec<Value>) -> Result<usize, Error> {
+        self.mutate_array(path, |mut arr| {
+            arr.extend(values.iter().cloned());
+            let len = arr.len();
+            Ok((arr, len))
+        })
+    }
+
+    /// Removes and returns the element at `index` (Redis-style negative indices
+    /// supported, clamped to the array bounds). Index `-1` pops the last element.
+    pub fn arr_pop(&mut self, path: &str, index: i64) -> Result<Value, Error> {
+        self.mutate_array(path, |mut arr| {
+            if arr.is_empty() {
+                return Ok((arr, Value::Null));
+            }
+            let len = arr.len() as i64;
+            let idx = if index < 0 {
+                (len + index).max(0)
+            } else {
+                index.min(len - 1)
+            } as usize;
+            let popped = arr.remove(idx);
+            Ok((arr, popped))
+        })
+    }
+
+    /// Inserts `values` at `index` in place and returns the resulting array length.
+    pub fn arr_insert(&mut self, path: &str, index: i64, values: Vec<Value>) -> Result<usize, Error> {
+        self.mutate_array(path, |mut arr| {
+            let len = arr.len() as i64;
+            if index.abs() > len {
+                return Err((arr, "ERR index out of bounds".into()));
+            }
+            let idx = if index < 0 { len + index } else { index } as usize;
+            arr.splice(idx..idx, values.iter().cloned());
+            let new_len = arr.len();
+            Ok((arr, new_len))
+        })
+    }
+
+    /// Trims the array at `path` in place to the inclusive `[start, stop]` range
+    /// (Redis-style negative indices supported) and returns the new length.
+    pub fn arr_trim(&mut self, path: &str, start: i64, stop: i64) -> Result<usize, Error> {
+        self.mutate_array(path, |mut arr| {
+            let len = arr.len() as i64;
+            let start = if start < 0 { (len + start).max(0) } else { start.min(len) };
+            let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+            if len == 0 || stop < 0 || start > stop {
+                arr.clear();
+            } else {
+                let (start, stop) = (start as usize, stop as usize);
+                arr.drain(stop + 1..);
+                arr.drain(..start);
+            }
+            let new_len = arr.len();
+            Ok((arr, new_len))
+        })
+    }
+
     pub fn get_type(&self, path: &str) -> Result<String, Error> {
         let s = RedisJSON::value_name(self.get_doc(path)?);
         Ok(s.to_string())
     }
 
-    pub fn value_name(value: &Value) -> &str {
-        match value {
-            Value::Null => "null",
-            Value::Bool(_) => "boolean",
-            Value::Number(_) => "number",
-            Value::String(_) => "string",
-            Value::Array(_) => "array",
-            Value::Object(_) => "object",
-        }
+    pub fn value_name<T: SelectValue>(value: &T) -> &'static str {
+        value.type_name()
     }
 
     pub fn value_op<F>(&mut self, path: &str, mut fun: F) -> Result<Value, Error>
@@ -318,19 +712,13 @@ impl RedisJSON {
             // root needs special handling
             collect_fun(current_data)
         } else {
-            SelectorMut::new()
-                .str_path(path)
-                .and_then(|selector| {
-                    Ok(selector
-                        .value(current_data.clone())
-                        .replace_with(&mut |v| Some(collect_fun(v)))?
-                        .take()
-                        .unwrap_or(Value::Null))
-                })
-                .map_err(|e| {
-                    errors.push(e.into());
-                })
-                .unwrap_or(current_data)
+            crate::path_cache::replace_with(current_data.clone(), path, &mut |v| {
+                Some(collect_fun(v))
+            })
+            .map_err(|e| {
+                errors.push(e.into());
+            })
+            .unwrap_or(current_data)
         };
 
         match errors.len() {
@@ -341,25 +729,146 @@ impl RedisJSON {
     }
 
     pub fn get_memory<'a>(&'a self, path: &'a str) -> Result<usize, Error> {
-        // TODO add better calculation, handle wrappers, internals and length
-        let res = match self.get_doc(path)? {
-            Value::Null => 0,
-            Value::Bool(v) => mem::size_of_val(v),
-            Value::Number(v) => mem::size_of_val(v),
-            Value::String(v) => mem::size_of_val(v), 
-            Value::Array(v) => mem::size_of_val(v), 
-            Value::Object(v) => mem::size_of_val(v),
-        };
-        Ok(res.into())
+        self.memory_usage(path)
+    }
+
+    /// Estimated heap size, in bytes, of the value at `path` (used by
+    /// `JSON.DEBUG MEMORY`).
+    pub fn memory_usage(&self, path: &str) -> Result<usize, Error> {
+        Ok(Self::value_memory_usage(self.get_doc(path)?))
+    }
+
+    /// Estimated heap size, in bytes, of the whole document (used by the
+    /// type's `mem_usage` callback so `MEMORY USAGE` accounts for JSON keys).
+    pub(crate) fn document_memory_usage(&self) -> usize {
+        Self::value_memory_usage(&self.data)
+    }
+
+    /// Recursively estimates the heap footprint of `value`, in bytes: strings
+    /// count their allocated capacity, arrays/objects sum their elements plus
+    /// the `Vec`/`Map` entry bookkeeping, and object keys add their own
+    /// capacity.
+    fn value_memory_usage(value: &Value) -> usize {
+        let container_overhead = mem::size_of::<Value>();
+        match value {
+            Value::Null | Value::Bool(_) => container_overhead,
+            Value::Number(n) => container_overhead + mem::size_of_val(n),
+            Value::String(s) => container_overhead + s.capacity(),
+            Value::Array(arr) => {
+                container_overhead
+                    + arr
+                        .iter()
+                        .map(|v| container_overhead + Self::value_memory_usage(v))
+                        .sum::<usize>()
+            }
+            Value::Object(map) => {
+                container_overhead
+                    + map
+                        .iter()
+                        .map(|(k, v)| {
+                            container_overhead + k.capacity() + Self::value_memory_usage(v)
+                        })
+                        .sum::<usize>()
+            }
+        }
     }
 
     pub fn get_doc<'a>(&'a self, path: &'a str) -> Result<&'a Value, Error> {
-        let results = jsonpath_lib::select(&self.data, path)?;
+        let results = crate::path_cache::select(&self.data, path)?;
         match results.first() {
             Some(s) => Ok(s),
             None => Err("ERR path does not exist".into()),
         }
     }
+
+    /// Returns every value matched by `path`, in document order. A legacy path
+    /// (see `is_legacy_path`) always matches at most one value; an enhanced path
+    /// with wildcards/recursive-descent/filters can match any number of them.
+    ///
+    /// Goes through the global compiled-path cache (see `path_cache`), so
+    /// repeated queries against the same path skip re-parsing it.
+    pub fn select<'a>(&'a self, path: &'a str) -> Result<Vec<&'a Value>, Error> {
+        Ok(crate::path_cache::select(&self.data, path)?)
+    }
+
+    /// A path is "legacy" (RedisJSON 1.x dotted-path style) when it can only ever
+    /// select a single value: no wildcards, no recursive descent, no filter
+    /// expressions, no slices and no unions. Legacy paths keep the old scalar
+    /// reply shape; anything else replies with an array of matches, one per
+    /// match.
+    ///
+    /// This only affects the *reply shape* of read commands (`JSON.TYPE`,
+    /// `JSON.STRLEN`/`ARRLEN`/`OBJLEN`, `JSON.OBJKEYS`); write commands
+    /// (`JSON.SET`, `JSON.NUMINCRBY`/`NUMMULTBY`/`NUMPOWBY`, `JSON.STRAPPEND`)
+    /// apply to every match, legacy or not, and those that reply with a single
+    /// value report the last match's result.
+    ///
+    /// A small quote-aware walk, not a full JSONPath parse: it tracks whether
+    /// it's inside a quoted member name so a wildcard/colon/recursive-descent
+    /// byte quoted as part of a key (e.g. `$['a:b']`) doesn't get mistaken for
+    /// the operator of the same shape.
+    pub fn is_legacy_path(path: &str) -> bool {
+        let mut quote = None;
+        let mut prev = '\0';
+        let mut chars = path.chars().peekable();
+        while let Some(c) = chars.next() {
+            if let Some(q) = quote {
+                if c == '\\' {
+                    chars.next(); // skip the escaped character
+                    continue;
+                }
+                if c == q {
+                    quote = None;
+                }
+                prev = '\0';
+                continue;
+            }
+            match c {
+                '\'' | '"' => quote = Some(c),
+                '*' | '?' | ':' | ',' => return false,
+                '.' if prev == '.' => return false,
+                _ => {}
+            }
+            prev = c;
+        }
+        true
+    }
+
+    /// Translates the value at `path` into a native RESP reply instead of a
+    /// JSON string: objects become `["{", key, value, key, value, ...]`,
+    /// arrays become `["[", elem, elem, ...]`, numbers that fit in `i64` become
+    /// RESP integers (other numbers fall back to a bulk string), booleans
+    /// become the bulk strings `"true"`/`"false"`, and `null` becomes RESP nil.
+    pub fn resp_serialize(&self, path: &str) -> Result<RedisValue, Error> {
+        Ok(Self::value_to_resp(self.get_doc(path)?))
+    }
+
+    fn value_to_resp(value: &Value) -> RedisValue {
+        match value {
+            Value::Null => ().into(),
+            Value::Bool(b) => if *b { "true" } else { "false" }.to_string().into(),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => i.into(),
+                None => n.to_string().into(),
+            },
+            Value::String(s) => s.clone().into(),
+            Value::Array(arr) => {
+                let mut items = Vec::with_capacity(arr.len() + 1);
+                items.push("[".to_string().into());
+                items.extend(arr.iter().map(Self::value_to_resp));
+                items.into()
+            }
+            Value::Object(map) => {
+                let mut items = Vec::with_capacity(map.len() * 2 + 1);
+                items.push("{".to_string().into());
+                for (k, v) in map {
+                    items.push(k.clone().into());
+                    items.push(Self::value_to_resp(v));
+                }
+                items.into()
+            }
+        }
+    }
 }
 
 #[allow(non_snake_case, unused)]
@@ -380,6 +889,13 @@ pub unsafe extern "C" fn json_free(value: *mut c_void) {
     Box::from_raw(value as *mut RedisJSON);
 }
 
+#[allow(non_snake_case, unused)]
+#[no_mangle]
+pub unsafe extern "C" fn json_mem_usage(value: *const c_void) -> usize {
+    let json = &*(value as *const RedisJSON);
+    json.document_memory_usage()
+}
+
 #[allow(non_snake_case, unused)]
 #[no_mangle]
 pub unsafe extern "C" fn json_rdb_save(rdb: *mut raw::RedisModuleIO, value: *mut c_void) {
@@ -0,0 +1,94 @@
+// Abstraction over the concrete value tree a document is stored as.
+//
+// The command layer (str_len/arr_len/obj_len/obj_keys/arr_index/value_name/...) is
+// written against this trait rather than matching on `serde_json::Value` variants
+// directly, so an alternative in-memory representation can be plugged in later
+// without touching command semantics. `serde_json::Value` remains the only
+// implementation today; `jsonpath_lib::select`/`replace_with` are still hardwired
+// to it, so `RedisJSON` itself keeps storing a concrete `Value`.
+//
+// `RedisJSON::get_doc` and `RedisJSON::value_op` were *not* converted to this
+// trait, unlike the accessors above, because their callers need a concrete
+// `serde_json::Value`, not just a `SelectValue`: `get_doc`'s results feed
+// `serde::Serialize` (JSON/YAML/MessagePack output), and `value_op`'s mutator
+// closures hand back a `Value` that gets spliced straight into the document
+// tree. Neither of those is expressible against the trait without `Serialize`
+// and "build a new node" becoming part of its surface, so they stay scoped to
+// `Value` until/unless a second backend actually needs them generic too.
+
+use serde_json::Value;
+
+#[derive(Debug, PartialEq)]
+pub enum SelectValueType {
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+pub trait SelectValue {
+    fn value_type(&self) -> SelectValueType;
+    fn type_name(&self) -> &'static str {
+        match self.value_type() {
+            SelectValueType::Null => "null",
+            SelectValueType::Bool => "boolean",
+            SelectValueType::Number => "number",
+            SelectValueType::String => "string",
+            SelectValueType::Array => "array",
+            SelectValueType::Object => "object",
+        }
+    }
+
+    fn as_str(&self) -> Option<&str>;
+    fn as_f64(&self) -> Option<f64>;
+    fn as_array(&self) -> Option<&Vec<Value>>;
+
+    /// Length for whichever container type this value is (string bytes, array
+    /// elements, or object keys); `None` for scalars.
+    fn len(&self) -> Option<usize>;
+
+    /// Object keys in their original insertion order; `None` for non-objects.
+    fn keys(&self) -> Option<Box<dyn Iterator<Item = &String> + '_>>;
+}
+
+impl SelectValue for Value {
+    fn value_type(&self) -> SelectValueType {
+        match self {
+            Value::Null => SelectValueType::Null,
+            Value::Bool(_) => SelectValueType::Bool,
+            Value::Number(_) => SelectValueType::Number,
+            Value::String(_) => SelectValueType::String,
+            Value::Array(_) => SelectValueType::Array,
+            Value::Object(_) => SelectValueType::Object,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        Value::as_str(self)
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        Value::as_f64(self)
+    }
+
+    fn as_array(&self) -> Option<&Vec<Value>> {
+        Value::as_array(self)
+    }
+
+    fn len(&self) -> Option<usize> {
+        match self {
+            Value::String(s) => Some(s.len()),
+            Value::Array(arr) => Some(arr.len()),
+            Value::Object(obj) => Some(obj.len()),
+            _ => None,
+        }
+    }
+
+    fn keys(&self) -> Option<Box<dyn Iterator<Item = &String> + '_>> {
+        self.as_object().map(|obj| {
+            Box::new(obj.keys()) as Box<dyn Iterator<Item = &String> + '_>
+        })
+    }
+}